@@ -0,0 +1,131 @@
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libc::{suseconds_t, time_t, timeval};
+
+#[cfg(feature = "wrap_sys_device")]
+use std::os::unix::io::RawFd;
+
+#[cfg(feature = "wrap_sys_device")]
+use libc::intptr_t;
+
+use libusb::*;
+
+use device_handle::{self, DeviceHandle};
+
+/// Trait for context types that own (or share ownership of) a `libusb_context`.
+///
+/// `Device` and `DeviceHandle` are generic over this trait instead of borrowing a `Context`
+/// directly. This lets a `Device<T>` hold its context by value, so it can outlive the scope that
+/// created the context and move freely across thread boundaries, while the underlying
+/// `libusb_context` is only closed once the last clone of `T` referencing it is dropped.
+pub trait UsbContext: Clone + Sized + Send + Sync {
+    #[doc(hidden)]
+    fn as_raw(&self) -> *mut libusb_context;
+}
+
+struct ContextInner {
+    context: *mut libusb_context,
+}
+
+unsafe impl Send for ContextInner {}
+unsafe impl Sync for ContextInner {}
+
+impl Drop for ContextInner {
+    /// Closes the `libusb` context.
+    fn drop(&mut self) {
+        unsafe {
+            libusb_exit(self.context);
+        }
+    }
+}
+
+/// A `libusb` context.
+///
+/// Cloning a `Context` is cheap: clones share the same underlying `libusb_context`, which is
+/// closed once the last clone is dropped. This makes it possible to hand a `Context` to a
+/// `Device` or `DeviceHandle` by value and keep using it elsewhere at the same time.
+#[derive(Clone)]
+pub struct Context {
+    inner: Arc<ContextInner>,
+}
+
+impl UsbContext for Context {
+    fn as_raw(&self) -> *mut libusb_context {
+        self.inner.context
+    }
+}
+
+impl Context {
+    /// Opens a new `libusb` context.
+    pub fn new() -> ::Result<Self> {
+        let mut context: *mut libusb_context = unsafe { mem::uninitialized() };
+
+        try_unsafe!(libusb_init(&mut context));
+
+        Ok(Context {
+            inner: Arc::new(ContextInner { context: context }),
+        })
+    }
+
+    /// Wraps an already-open platform file descriptor and obtains a `DeviceHandle` for the
+    /// underlying device, without `libusb` ever calling `open()` itself.
+    ///
+    /// This is for sandboxed environments where a privileged broker process opens the USB device
+    /// node and hands the resulting file descriptor to an unprivileged worker that has no
+    /// permission to open the node directly. There is no `libusb_device` backing the returned
+    /// handle, so it has to be created from the `Context` rather than from a `Device`.
+    ///
+    /// The returned handle does not take ownership of `fd`: dropping it closes the underlying
+    /// `libusb` handle, but `fd` itself is left open, and the caller remains responsible for
+    /// closing it once the handle is no longer in use.
+    ///
+    /// Requires `libusb` >= 1.0.23 and this crate's `wrap_sys_device` feature.
+    #[cfg(feature = "wrap_sys_device")]
+    pub fn wrap_sys_device(&self, fd: RawFd) -> ::Result<DeviceHandle<Self>> {
+        let mut handle: *mut libusb_device_handle = unsafe { mem::uninitialized() };
+
+        try_unsafe!(libusb_wrap_sys_device(
+            self.as_raw(),
+            fd as intptr_t,
+            &mut handle
+        ));
+
+        Ok(unsafe { device_handle::from_libusb(self.clone(), handle) })
+    }
+
+    /// Pumps `libusb`'s event sources once, blocking until at least one event (a completed
+    /// `Transfer`, a hotplug notification, ...) has been handled, or until `timeout` elapses if
+    /// given.
+    ///
+    /// Submitted transfers and registered hotplug callbacks only actually fire while some thread
+    /// is inside this call; long-running applications typically dedicate a thread to calling it
+    /// in a loop.
+    pub fn handle_events(&self, timeout: Option<Duration>) -> ::Result<()> {
+        match timeout {
+            Some(timeout) => {
+                let tv = timeval {
+                    tv_sec: timeout.as_secs() as time_t,
+                    tv_usec: timeout.subsec_micros() as suseconds_t,
+                };
+
+                try_unsafe!(libusb_handle_events_timeout(self.as_raw(), &tv));
+            }
+            None => {
+                try_unsafe!(libusb_handle_events(self.as_raw()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("context", &self.inner.context)
+            .finish()
+    }
+}
@@ -1,21 +1,21 @@
-use std::marker::PhantomData;
+use std::fmt;
 use std::mem;
 
 use libusb::*;
 
 use config_descriptor::{self, ConfigDescriptor};
-use context::Context;
+use context::UsbContext;
 use device_descriptor::{self, DeviceDescriptor};
 use device_handle::{self, DeviceHandle};
 use fields::{self, Speed};
 
 /// A reference to a USB device.
-pub struct Device<'a> {
-    context: PhantomData<&'a Context>,
+pub struct Device<T: UsbContext> {
+    context: T,
     device: *mut libusb_device,
 }
 
-impl<'a> Drop for Device<'a> {
+impl<T: UsbContext> Drop for Device<T> {
     /// Releases the device reference.
     fn drop(&mut self) {
         unsafe {
@@ -24,10 +24,29 @@ impl<'a> Drop for Device<'a> {
     }
 }
 
-unsafe impl<'a> Send for Device<'a> {}
-unsafe impl<'a> Sync for Device<'a> {}
+unsafe impl<T: UsbContext> Send for Device<T> {}
+unsafe impl<T: UsbContext> Sync for Device<T> {}
 
-impl<'a> Device<'a> {
+impl<T: UsbContext> PartialEq for Device<T> {
+    /// Returns `true` if the two devices refer to the same underlying `libusb_device`.
+    fn eq(&self, other: &Self) -> bool {
+        self.device == other.device
+    }
+}
+
+impl<T: UsbContext> Eq for Device<T> {}
+
+impl<T: UsbContext> fmt::Debug for Device<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("bus_number", &self.bus_number())
+            .field("address", &self.address())
+            .field("port_number", &self.port_number())
+            .finish()
+    }
+}
+
+impl<T: UsbContext> Device<T> {
     /// Reads the device descriptor.
     pub fn device_descriptor(&self) -> ::Result<DeviceDescriptor> {
         let mut descriptor: libusb_device_descriptor = unsafe { mem::uninitialized() };
@@ -99,12 +118,12 @@ impl<'a> Device<'a> {
     }
 
     /// Opens the device.
-    pub fn open(&self) -> ::Result<DeviceHandle<'a>> {
+    pub fn open(&self) -> ::Result<DeviceHandle<T>> {
         let mut handle: *mut libusb_device_handle = unsafe { mem::uninitialized() };
 
         try_unsafe!(libusb_open(self.device, &mut handle));
 
-        Ok(unsafe { device_handle::from_libusb(self.context, handle) })
+        Ok(unsafe { device_handle::from_libusb(self.context.clone(), handle) })
     }
 
     /// Returns the parent device
@@ -115,7 +134,7 @@ impl<'a> Device<'a> {
                 None
             } else {
                 Some(Self {
-                    context: self.context,
+                    context: self.context.clone(),
                     device: parent,
                 })
             }
@@ -124,10 +143,7 @@ impl<'a> Device<'a> {
 }
 
 #[doc(hidden)]
-pub unsafe fn from_libusb<'a>(
-    context: PhantomData<&'a Context>,
-    device: *mut libusb_device,
-) -> Device<'a> {
+pub unsafe fn from_libusb<T: UsbContext>(context: T, device: *mut libusb_device) -> Device<T> {
     libusb_ref_device(device);
 
     Device {
@@ -0,0 +1,44 @@
+use libusb::*;
+
+use context::UsbContext;
+
+/// A handle to an open USB device.
+pub struct DeviceHandle<T: UsbContext> {
+    context: T,
+    handle: *mut libusb_device_handle,
+}
+
+impl<T: UsbContext> Drop for DeviceHandle<T> {
+    /// Closes the device.
+    fn drop(&mut self) {
+        unsafe {
+            libusb_close(self.handle);
+        }
+    }
+}
+
+unsafe impl<T: UsbContext> Send for DeviceHandle<T> {}
+unsafe impl<T: UsbContext> Sync for DeviceHandle<T> {}
+
+impl<T: UsbContext> DeviceHandle<T> {
+    #[doc(hidden)]
+    pub(crate) fn as_raw(&self) -> *mut libusb_device_handle {
+        self.handle
+    }
+
+    #[doc(hidden)]
+    pub(crate) fn context(&self) -> T {
+        self.context.clone()
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn from_libusb<T: UsbContext>(
+    context: T,
+    handle: *mut libusb_device_handle,
+) -> DeviceHandle<T> {
+    DeviceHandle {
+        context: context,
+        handle: handle,
+    }
+}
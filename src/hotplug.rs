@@ -0,0 +1,136 @@
+use std::mem;
+use std::os::raw::c_void;
+use std::panic;
+
+use libusb::*;
+
+use context::{Context, UsbContext};
+use device::{self, Device};
+
+/// The kind of hotplug event a registered callback was invoked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HotplugEvent {
+    /// A new device matching the registration's filter has been connected.
+    DeviceArrived,
+
+    /// A device matching the registration's filter has been disconnected.
+    DeviceLeft,
+}
+
+type Callback<T> = Box<dyn FnMut(Device<T>, HotplugEvent) + Send>;
+
+struct CallbackData<T: UsbContext> {
+    context: T,
+    callback: Callback<T>,
+}
+
+/// A handle to a registered hotplug callback.
+///
+/// Dropping a `Registration` deregisters the callback. Once dropped, the closure passed to
+/// `Context::register_callback` is no longer invoked and is released.
+pub struct Registration<T: UsbContext> {
+    context: T,
+    handle: libusb_hotplug_callback_handle,
+    data: *mut CallbackData<T>,
+}
+
+unsafe impl<T: UsbContext> Send for Registration<T> {}
+unsafe impl<T: UsbContext> Sync for Registration<T> {}
+
+impl<T: UsbContext> Drop for Registration<T> {
+    /// Deregisters the callback.
+    fn drop(&mut self) {
+        unsafe {
+            libusb_hotplug_deregister_callback(self.context.as_raw(), self.handle);
+            drop(Box::from_raw(self.data));
+        }
+    }
+}
+
+impl Context {
+    /// Registers a closure to be called when a device matching the given filter is connected or
+    /// disconnected.
+    ///
+    /// `vendor_id`, `product_id` and `class` filter which devices the callback is invoked for;
+    /// pass `None` for any of them to match any value, as libusb's `LIBUSB_HOTPLUG_MATCH_ANY`
+    /// does. The callback also fires once for every already-connected device that matches the
+    /// filter at registration time.
+    ///
+    /// Returns a [`Registration`] that deregisters the callback when dropped. Requires a `libusb`
+    /// build with hotplug support; returns an error otherwise.
+    pub fn register_callback<F>(
+        &self,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        class: Option<u8>,
+        callback: F,
+    ) -> ::Result<Registration<Self>>
+    where
+        F: FnMut(Device<Self>, HotplugEvent) + Send + 'static,
+    {
+        if unsafe { libusb_has_capability(LIBUSB_CAP_HAS_HOTPLUG) } == 0 {
+            return Err(::error::from_libusb(LIBUSB_ERROR_NOT_SUPPORTED));
+        }
+
+        let data = Box::into_raw(Box::new(CallbackData {
+            context: self.clone(),
+            callback: Box::new(callback),
+        }));
+
+        let mut handle: libusb_hotplug_callback_handle = unsafe { mem::uninitialized() };
+
+        let rc = unsafe {
+            libusb_hotplug_register_callback(
+                self.as_raw(),
+                LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED | LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT,
+                LIBUSB_HOTPLUG_ENUMERATE,
+                vendor_id.map_or(LIBUSB_HOTPLUG_MATCH_ANY, |id| id as i32),
+                product_id.map_or(LIBUSB_HOTPLUG_MATCH_ANY, |id| id as i32),
+                class.map_or(LIBUSB_HOTPLUG_MATCH_ANY, |class| class as i32),
+                hotplug_trampoline::<Self>,
+                data as *mut c_void,
+                &mut handle,
+            )
+        };
+
+        if rc != 0 {
+            drop(unsafe { Box::from_raw(data) });
+            return Err(::error::from_libusb(rc));
+        }
+
+        Ok(Registration {
+            context: self.clone(),
+            handle: handle,
+            data: data,
+        })
+    }
+}
+
+/// Trampoline invoked by `libusb` on its event thread; recovers the boxed closure from
+/// `user_data` and dispatches to it. Must not unwind across the FFI boundary.
+extern "C" fn hotplug_trampoline<T: UsbContext>(
+    _ctx: *mut libusb_context,
+    device: *mut libusb_device,
+    event: libusb_hotplug_event,
+    user_data: *mut c_void,
+) -> i32 {
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| unsafe {
+        let data = &mut *(user_data as *mut CallbackData<T>);
+
+        let event = if event == LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED {
+            HotplugEvent::DeviceArrived
+        } else {
+            HotplugEvent::DeviceLeft
+        };
+
+        let device = device::from_libusb(data.context.clone(), device);
+
+        (data.callback)(device, event);
+    }));
+
+    if result.is_err() {
+        eprintln!("libusb hotplug callback panicked");
+    }
+
+    0
+}
@@ -0,0 +1,386 @@
+use std::os::raw::c_void;
+use std::panic;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use libusb::*;
+
+use context::UsbContext;
+use device_handle::DeviceHandle;
+
+/// The number of bytes in a USB control transfer's setup packet (bmRequestType, bRequest,
+/// wValue, wIndex, wLength).
+const CONTROL_SETUP_SIZE: usize = 8;
+
+/// The kind of endpoint an asynchronous [`Transfer`] talks to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    /// A control transfer. `buffer` must start with an 8-byte setup packet (as built by
+    /// `libusb_fill_control_setup`); its `bmRequestType` — not `endpoint` — determines the
+    /// transfer's direction, and its `wLength` must agree with the data phase capacity/length
+    /// following the setup packet.
+    Control,
+    Bulk,
+    Interrupt,
+    /// An isochronous transfer of `num_packets` equally-sized packets. `buffer`'s length (for
+    /// OUT) or capacity (for IN) must be evenly divisible by `num_packets`.
+    Isochronous { num_packets: u32 },
+}
+
+impl TransferType {
+    fn to_libusb(self) -> u8 {
+        (match self {
+            TransferType::Control => LIBUSB_TRANSFER_TYPE_CONTROL,
+            TransferType::Bulk => LIBUSB_TRANSFER_TYPE_BULK,
+            TransferType::Interrupt => LIBUSB_TRANSFER_TYPE_INTERRUPT,
+            TransferType::Isochronous { .. } => LIBUSB_TRANSFER_TYPE_ISOCHRONOUS,
+        }) as u8
+    }
+}
+
+/// The outcome of a completed [`Transfer`], read from `libusb_transfer::status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferStatus {
+    Completed,
+    Error,
+    TimedOut,
+    Cancelled,
+    Stall,
+    NoDevice,
+    Overflow,
+}
+
+fn status_from_libusb(status: libusb_transfer_status) -> TransferStatus {
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => TransferStatus::Completed,
+        LIBUSB_TRANSFER_TIMED_OUT => TransferStatus::TimedOut,
+        LIBUSB_TRANSFER_CANCELLED => TransferStatus::Cancelled,
+        LIBUSB_TRANSFER_STALL => TransferStatus::Stall,
+        LIBUSB_TRANSFER_NO_DEVICE => TransferStatus::NoDevice,
+        LIBUSB_TRANSFER_OVERFLOW => TransferStatus::Overflow,
+        _ => TransferStatus::Error,
+    }
+}
+
+/// Returns whether `endpoint` is an IN endpoint (device-to-host), per the USB endpoint address
+/// convention `libusb` itself follows.
+fn endpoint_is_in(endpoint: u8) -> bool {
+    endpoint & LIBUSB_ENDPOINT_IN == LIBUSB_ENDPOINT_IN
+}
+
+/// For a control transfer, direction comes from the setup packet's `bmRequestType` (the buffer's
+/// first byte), not from the endpoint address (always EP0).
+fn control_setup_is_in(buffer: &[u8]) -> bool {
+    buffer
+        .get(0)
+        .map_or(false, |bm_request_type| endpoint_is_in(*bm_request_type))
+}
+
+/// Equivalent of `libusb`'s `libusb_set_iso_packet_lengths` static inline helper, which isn't a
+/// linkable symbol: fills in every packet descriptor's `length` field by hand.
+unsafe fn set_iso_packet_lengths(transfer: *mut libusb_transfer, length: u32) {
+    let descriptors = (*transfer).iso_packet_desc.as_mut_ptr();
+
+    for i in 0..(*transfer).num_iso_packets {
+        (*descriptors.offset(i as isize)).length = length;
+    }
+}
+
+/// The `transfer` pointer only ever lives in `Pending` or `InFlight`; once the transfer reaches
+/// `Done`, the state no longer holds it, and the completion callback passes it to
+/// `libusb_free_transfer` right after storing `Done`. Gating
+/// every access to the raw pointer on which variant is currently stored (under `Inner::state`'s
+/// mutex) is what keeps `TransferCanceller::cancel` from touching a freed transfer.
+enum State {
+    Pending(*mut libusb_transfer, Vec<u8>),
+    InFlight(*mut libusb_transfer, Vec<u8>),
+    Done(Vec<u8>, TransferStatus, usize),
+}
+
+struct Inner {
+    state: Mutex<State>,
+}
+
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+/// A thread-safe, clonable handle that can cancel an in-flight [`Transfer`].
+#[derive(Clone)]
+pub struct TransferCanceller {
+    inner: Arc<Inner>,
+}
+
+unsafe impl Send for TransferCanceller {}
+unsafe impl Sync for TransferCanceller {}
+
+impl TransferCanceller {
+    /// Requests cancellation of the transfer. The transfer completes with
+    /// `TransferStatus::Cancelled` once `libusb` has processed the request; it must still be
+    /// reclaimed through `Context::handle_events` and `Transfer::try_reclaim` like any other
+    /// completed transfer.
+    ///
+    /// Returns an error (without touching `libusb`) if the transfer has already reached a
+    /// terminal state, since by then `libusb_free_transfer` may already have run and the
+    /// transfer pointer is no longer valid.
+    pub fn cancel(&self) -> ::Result<()> {
+        let state = self.inner.state.lock().unwrap();
+
+        let transfer = match &*state {
+            State::InFlight(transfer, _) => *transfer,
+            _ => return Err(::error::from_libusb(LIBUSB_ERROR_NOT_FOUND)),
+        };
+
+        try_unsafe!(libusb_cancel_transfer(transfer));
+        Ok(())
+    }
+}
+
+/// An asynchronous USB transfer, mirroring `libusb_alloc_transfer` / `libusb_submit_transfer` /
+/// `libusb_cancel_transfer`.
+///
+/// The transfer owns its data buffer for as long as `libusb` might still be writing to it: the
+/// buffer lives on the heap independently of this struct, so moving or dropping a submitted
+/// `Transfer` does not move or free the memory `libusb` holds a pointer to; the underlying
+/// `libusb_transfer` is only freed once, either when the `Transfer` is dropped if it was never
+/// submitted (or its submission failed), or by the completion callback once the event loop has
+/// driven it to a terminal state.
+///
+/// A `Transfer` also borrows the `DeviceHandle` it was created from for its whole lifetime, since
+/// `libusb` holds a raw `dev_handle` pointer into it for as long as the transfer is in flight.
+/// Dropping an in-flight `Transfer` cancels it and drives `Context::handle_events` until it
+/// reaches a terminal state, so that the borrow is not released — and the handle cannot be
+/// closed — while `libusb` might still touch it.
+pub struct Transfer<'d, T: UsbContext + 'd> {
+    // Never read directly: kept only to hold the borrow that ties this transfer's lifetime to
+    // the `DeviceHandle` `libusb` has a raw pointer to for as long as it might be in flight.
+    _device: &'d DeviceHandle<T>,
+    context: T,
+    inner: Arc<Inner>,
+}
+
+impl<'d, T: UsbContext> Transfer<'d, T> {
+    /// Allocates a new, not-yet-submitted transfer for `endpoint` on `device`.
+    ///
+    /// For an IN endpoint, `buffer`'s *capacity* is the number of bytes `libusb` is allowed to
+    /// write; for an OUT endpoint, only `buffer`'s initialized *length* is transmitted, so spare
+    /// capacity is never read or sent. See [`TransferType::Control`] and
+    /// [`TransferType::Isochronous`] for the extra constraints those kinds place on `buffer`.
+    pub fn new(
+        device: &'d DeviceHandle<T>,
+        endpoint: u8,
+        kind: TransferType,
+        mut buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> ::Result<Self> {
+        if let TransferType::Control = kind {
+            if buffer.len() < CONTROL_SETUP_SIZE {
+                return Err(::error::from_libusb(LIBUSB_ERROR_INVALID_PARAM));
+            }
+        }
+
+        let num_iso_packets = match kind {
+            TransferType::Isochronous { num_packets } => num_packets,
+            _ => 0,
+        };
+
+        let transfer = unsafe { libusb_alloc_transfer(num_iso_packets as i32) };
+
+        if transfer.is_null() {
+            return Err(::error::from_libusb(LIBUSB_ERROR_NO_MEM));
+        }
+
+        let is_in = match kind {
+            TransferType::Control => control_setup_is_in(&buffer),
+            _ => endpoint_is_in(endpoint),
+        };
+
+        let length = if is_in { buffer.capacity() } else { buffer.len() };
+
+        unsafe {
+            (*transfer).dev_handle = device.as_raw();
+            (*transfer).endpoint = endpoint;
+            (*transfer).transfer_type = kind.to_libusb();
+            (*transfer).timeout = timeout.as_millis() as u32;
+            (*transfer).buffer = buffer.as_mut_ptr();
+            (*transfer).length = length as i32;
+            (*transfer).callback = transfer_trampoline;
+            (*transfer).user_data = ptr::null_mut();
+
+            if let TransferType::Isochronous { num_packets } = kind {
+                if num_packets == 0 || length % num_packets as usize != 0 {
+                    libusb_free_transfer(transfer);
+                    return Err(::error::from_libusb(LIBUSB_ERROR_INVALID_PARAM));
+                }
+
+                (*transfer).num_iso_packets = num_packets as i32;
+                set_iso_packet_lengths(transfer, (length / num_packets as usize) as u32);
+            }
+        }
+
+        Ok(Transfer {
+            _device: device,
+            context: device.context(),
+            inner: Arc::new(Inner {
+                state: Mutex::new(State::Pending(transfer, buffer)),
+            }),
+        })
+    }
+
+    /// Submits the transfer to `libusb` and returns a [`TransferCanceller`] for it.
+    ///
+    /// The transfer runs on `libusb`'s event thread; drive it to completion with
+    /// `Context::handle_events` and retrieve the result with `try_reclaim`. Fails with an error
+    /// if the transfer has already been submitted (or has already completed).
+    pub fn submit(&self) -> ::Result<TransferCanceller> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        let (transfer, buffer) =
+            match ::std::mem::replace(&mut *state, State::Done(Vec::new(), TransferStatus::Error, 0))
+            {
+                State::Pending(transfer, buffer) => (transfer, buffer),
+                other => {
+                    *state = other;
+                    return Err(::error::from_libusb(LIBUSB_ERROR_BUSY));
+                }
+            };
+
+        unsafe {
+            (*transfer).user_data = Arc::into_raw(self.inner.clone()) as *mut c_void;
+        }
+
+        let rc = unsafe { libusb_submit_transfer(transfer) };
+
+        if rc != 0 {
+            unsafe {
+                drop(Arc::from_raw((*transfer).user_data as *const Inner));
+                (*transfer).user_data = ptr::null_mut();
+            }
+
+            *state = State::Pending(transfer, buffer);
+            return Err(::error::from_libusb(rc));
+        }
+
+        *state = State::InFlight(transfer, buffer);
+        drop(state);
+
+        Ok(TransferCanceller {
+            inner: self.inner.clone(),
+        })
+    }
+
+    /// Reclaims the buffer and result once the transfer has reached a terminal state, or hands
+    /// `self` back unchanged if it is still pending.
+    ///
+    /// For an IN transfer, the returned buffer's length is the number of bytes `libusb` actually
+    /// received (`actual_length`, plus the 8-byte setup packet for `TransferType::Control`), not
+    /// the capacity requested when the transfer was created. For `TransferType::Isochronous`,
+    /// `actual_length` is `libusb`'s transfer-wide figure; per-packet status and lengths are not
+    /// exposed by this type.
+    pub fn try_reclaim(self) -> Result<(Vec<u8>, TransferStatus, usize), Self> {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            match ::std::mem::replace(&mut *state, State::Done(Vec::new(), TransferStatus::Error, 0))
+            {
+                State::Done(buffer, status, actual_length) => {
+                    return Ok((buffer, status, actual_length));
+                }
+                other => {
+                    *state = other;
+                }
+            }
+        }
+
+        Err(self)
+    }
+
+    fn is_done(&self) -> bool {
+        match *self.inner.state.lock().unwrap() {
+            State::Done(..) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'d, T: UsbContext> Drop for Transfer<'d, T> {
+    fn drop(&mut self) {
+        let in_flight = {
+            let state = self.inner.state.lock().unwrap();
+            match &*state {
+                State::Pending(transfer, _) => {
+                    unsafe { libusb_free_transfer(*transfer) };
+                    false
+                }
+                State::InFlight(transfer, _) => {
+                    unsafe { libusb_cancel_transfer(*transfer) };
+                    true
+                }
+                State::Done(..) => false,
+            }
+        };
+
+        // `device` (and the `libusb_device_handle` it closes on drop) must not go away before
+        // `libusb` is done with this transfer, but that's a runtime fact `libusb`'s callback
+        // tells us about, not something the `&'d DeviceHandle<T>` borrow alone can guarantee past
+        // this point. So for an in-flight transfer, pump events ourselves until the completion
+        // trampoline has recorded its result, blocking the drop if necessary.
+        while in_flight && !self.is_done() {
+            if unsafe { libusb_handle_events(self.context.as_raw()) } != 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Trampoline invoked by `libusb` when a transfer completes; recovers the transfer's shared
+/// state from `user_data`, records the result, and frees the `libusb_transfer`. Must not unwind
+/// across the FFI boundary.
+extern "C" fn transfer_trampoline(transfer: *mut libusb_transfer) {
+    let result = panic::catch_unwind(|| unsafe {
+        let inner = Arc::from_raw((*transfer).user_data as *const Inner);
+
+        let status = status_from_libusb((*transfer).status);
+        let transfer_type = (*transfer).transfer_type;
+        let actual_length = (*transfer).actual_length as usize;
+
+        let mut state = inner.state.lock().unwrap();
+        match ::std::mem::replace(&mut *state, State::Done(Vec::new(), TransferStatus::Error, 0)) {
+            State::InFlight(_, mut buffer) => {
+                let is_in = if transfer_type == LIBUSB_TRANSFER_TYPE_CONTROL {
+                    control_setup_is_in(&buffer)
+                } else {
+                    endpoint_is_in((*transfer).endpoint)
+                };
+
+                // Bytes beyond what was actually received were never written to by `libusb` for
+                // an IN transfer; `set_len` is safe since the received length never exceeds the
+                // capacity `Transfer::new` handed to `libusb` as `transfer->length`.
+                if is_in {
+                    let received = if transfer_type == LIBUSB_TRANSFER_TYPE_CONTROL {
+                        CONTROL_SETUP_SIZE + actual_length
+                    } else {
+                        actual_length
+                    };
+
+                    buffer.set_len(received);
+                }
+
+                *state = State::Done(buffer, status, actual_length);
+            }
+            other => {
+                // Not actually in flight (shouldn't happen); leave the state as it was.
+                *state = other;
+            }
+        }
+        drop(state);
+
+        // Freeing here, after the state no longer holds the pointer, is what makes it safe for
+        // `TransferCanceller::cancel` to check the state instead of the raw pointer directly.
+        libusb_free_transfer(transfer);
+    });
+
+    if result.is_err() {
+        eprintln!("libusb transfer completion callback panicked");
+    }
+}